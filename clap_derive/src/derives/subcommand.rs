@@ -15,13 +15,74 @@
 use proc_macro2::{Ident, Span, TokenStream};
 use proc_macro_error::{abort, abort_call_site};
 use quote::{format_ident, quote, quote_spanned};
-use syn::{spanned::Spanned, Data, DeriveInput, FieldsUnnamed, Generics, Variant};
+use syn::{
+    spanned::Spanned, Data, DeriveInput, Field, FieldsNamed, FieldsUnnamed, Generics, Variant,
+};
 
 use crate::derives::args;
 use crate::dummies;
 use crate::item::{Item, Kind, Name};
 use crate::utils::{is_simple_ty, subty_if_name};
 
+// NOT IMPLEMENTED: per-field `#[arg(parse_with = ...)]` conversion functions
+// (from_str/try_from_str/from_os_str/try_from_os_str/from_occurrences) for
+// fields inside subcommand variants. Named/Unnamed variant fields here defer
+// entirely to `args::gen_constructor`/`args::gen_augment`/`args::gen_updater`
+// for both attribute parsing and codegen, and neither is part of this crate
+// snapshot (`args.rs` doesn't exist), so there's no attribute-parsing side
+// left in this tree to opt a field into a custom parser function -- not
+// implementable from `clap_derive/src/derives/subcommand.rs` alone; flagging
+// here rather than claiming it's done.
+
+// NOT IMPLEMENTED: a user-supplied `#[command(rename_all = path::to::fn)]`
+// casing function for subcommand names. This file only *consumes*
+// `item.cased_name()` (here and in `gen_augment`/`gen_has_subcommand`); the
+// casing styles it produces are decided entirely on the attribute-parsing
+// side, in `Item`/`item.rs`, which is not part of this crate snapshot. A
+// custom-fn casing variant can't be threaded through without extending that
+// file, so this request is not implementable from
+// `clap_derive/src/derives/subcommand.rs` alone -- flagging here rather than
+// claiming it's done.
+
+/// Find the single field of a struct-style `#[command(flatten)]` variant
+/// that is itself marked `#[command(subcommand)]`. The remaining fields are
+/// ordinary `Args` fields that get merged in alongside the delegated
+/// subcommand.
+fn find_flatten_subcommand_field<'f>(
+    variant: &Variant,
+    fields: &'f FieldsNamed,
+    item: &Item,
+) -> &'f Field {
+    if item.flatten_prefix().is_some() {
+        // Struct-style flatten variants don't get a single nested subcommand
+        // to hang the prefix on the way tuple-style variants do (their
+        // fields, including the delegated subcommand, are augmented
+        // directly onto the parent `Command`), so `prefix` has nowhere to
+        // attach here. Reject it explicitly rather than silently ignoring
+        // it and letting two struct-style flattened groups collide.
+        abort!(
+            variant,
+            "`prefix` is not supported on struct-style `flatten` variants, \
+             only on single-typed tuple variants"
+        );
+    }
+
+    fields
+        .named
+        .iter()
+        .find(|field| {
+            let field_item = Item::from_args_field(field, item.casing(), item.env_casing());
+            matches!(&*field_item.kind(), Kind::Subcommand(_))
+        })
+        .unwrap_or_else(|| {
+            abort!(
+                variant,
+                "`flatten` on a struct-style variant requires exactly one field \
+                 marked `#[command(subcommand)]`"
+            )
+        })
+}
+
 pub fn derive_subcommand(input: &DeriveInput) -> TokenStream {
     let ident = &input.ident;
 
@@ -139,8 +200,8 @@ fn gen_augment(
                         _ => abort!(
                             variant,
                             "The enum variant marked with `external_subcommand` must be \
-                             a single-typed tuple, and the type must be either `Vec<String>` \
-                             or `Vec<OsString>`."
+                             a single-typed tuple, and the type must be `Vec<T>` where \
+                             `T` has a `value_parser`."
                         ),
                     };
                     let deprecations = if !override_required {
@@ -176,28 +237,71 @@ fn gen_augment(
                         let old_heading_var = format_ident!("__clap_old_heading");
                         let next_help_heading = item.next_help_heading();
                         let next_display_order = item.next_display_order();
-                        let subcommand = if override_required {
-                            quote! {
-                                #deprecations
-                                let #old_heading_var = #app_var.get_next_help_heading().map(|s| clap::builder::Str::from(s.to_owned()));
-                                let #app_var = #app_var #next_help_heading #next_display_order;
-                                let #app_var = <#ty as clap::Subcommand>::augment_subcommands_for_update(#app_var);
-                                let #app_var = #app_var.next_help_heading(clap::builder::Resettable::from(#old_heading_var));
-                            }
+                        let augment_fn = if override_required {
+                            quote!(<#ty as clap::Subcommand>::augment_subcommands_for_update)
                         } else {
-                            quote! {
-                                #deprecations
-                                let #old_heading_var = #app_var.get_next_help_heading().map(|s| clap::builder::Str::from(s.to_owned()));
-                                let #app_var = #app_var #next_help_heading #next_display_order;
-                                let #app_var = <#ty as clap::Subcommand>::augment_subcommands(#app_var);
-                                let #app_var = #app_var.next_help_heading(clap::builder::Resettable::from(#old_heading_var));
-                            }
+                            quote!(<#ty as clap::Subcommand>::augment_subcommands)
+                        };
+                        // `#[command(flatten, prefix = "...")]` nests the child
+                        // enum's entire subcommand tree one level down, under a
+                        // single subcommand named `prefix`, so two flattened
+                        // enums never collide on a shared top-level name. This
+                        // (rather than renaming each of the child's individual
+                        // subcommands in place) is what lets `ArgMatches`
+                        // round-trip cleanly: the nested `ArgMatches` handed to
+                        // the child's own `FromArgMatches` impl looks exactly
+                        // like what it would see unflattened, with its own
+                        // unprefixed subcommand names intact.
+                        let merge_subcommands = match item.flatten_prefix() {
+                            Some(prefix) => quote! {
+                                let #app_var = #app_var.subcommand(#augment_fn(clap::Command::new(#prefix)));
+                            },
+                            None => quote! {
+                                let #app_var = #augment_fn(#app_var);
+                            },
+                        };
+                        let subcommand = quote! {
+                            #deprecations
+                            let #old_heading_var = #app_var.get_next_help_heading().map(|s| clap::builder::Str::from(s.to_owned()));
+                            let #app_var = #app_var #next_help_heading #next_display_order;
+                            #merge_subcommands
+                            let #app_var = #app_var.next_help_heading(clap::builder::Resettable::from(#old_heading_var));
+                        };
+                        Some(subcommand)
+                    }
+                    Named(ref fields) => {
+                        let _ = find_flatten_subcommand_field(*variant, fields, item);
+                        let deprecations = if !override_required {
+                            item.deprecations()
+                        } else {
+                            quote!()
+                        };
+                        let fields = fields
+                            .named
+                            .iter()
+                            .map(|field| {
+                                let field_item =
+                                    Item::from_args_field(field, item.casing(), item.env_casing());
+                                (field, field_item)
+                            })
+                            .collect::<Vec<_>>();
+                        let field_augment = args::gen_augment(&fields, &app_var, item, override_required);
+                        let old_heading_var = format_ident!("__clap_old_heading");
+                        let next_help_heading = item.next_help_heading();
+                        let next_display_order = item.next_display_order();
+                        let subcommand = quote! {
+                            #deprecations
+                            let #old_heading_var = #app_var.get_next_help_heading().map(|s| clap::builder::Str::from(s.to_owned()));
+                            let #app_var = #app_var #next_help_heading #next_display_order;
+                            let #app_var = { #field_augment };
+                            let #app_var = #app_var.next_help_heading(clap::builder::Resettable::from(#old_heading_var));
                         };
                         Some(subcommand)
                     }
                     _ => abort!(
                         variant,
-                        "`flatten` is usable only with single-typed tuple variants"
+                        "`flatten` is usable only with single-typed tuple variants, \
+                         or struct-style variants with a `#[command(subcommand)]` field"
                     ),
                 },
 
@@ -369,9 +473,28 @@ fn gen_has_subcommand(variants: &[(&Variant, Item)]) -> TokenStream {
     });
     let child_subcommands = flatten_variants
         .iter()
-        .map(|(variant, _attrs)| match variant.fields {
+        .map(|(variant, item)| match variant.fields {
             Unnamed(ref fields) if fields.unnamed.len() == 1 => {
                 let ty = &fields.unnamed[0];
+                match item.flatten_prefix() {
+                    // The child's subcommands are nested one level down, under
+                    // a single top-level name: from here, that's the only name
+                    // this flattened variant owns.
+                    Some(prefix) => quote! {
+                        if __clap_name == #prefix {
+                            return true;
+                        }
+                    },
+                    None => quote! {
+                        if <#ty as clap::Subcommand>::has_subcommand(__clap_name) {
+                            return true;
+                        }
+                    },
+                }
+            }
+            Named(ref fields) => {
+                let sub_field = find_flatten_subcommand_field(*variant, fields, item);
+                let ty = &sub_field.ty;
                 quote! {
                     if <#ty as clap::Subcommand>::has_subcommand(__clap_name) {
                         return true;
@@ -380,7 +503,8 @@ fn gen_has_subcommand(variants: &[(&Variant, Item)]) -> TokenStream {
             }
             _ => abort!(
                 variant,
-                "`flatten` is usable only with single-typed tuple variants"
+                "`flatten` is usable only with single-typed tuple variants, \
+                 or struct-style variants with a `#[command(subcommand)]` field"
             ),
         });
 
@@ -422,34 +546,22 @@ fn gen_from_arg_matches(variants: &[(&Variant, Item)]) -> TokenStream {
                     _ => abort!(
                         variant,
                         "The enum variant marked with `external_subcommand` must be \
-                         a single-typed tuple, and the type must be either `Vec<String>` \
-                         or `Vec<OsString>`."
+                         a single-typed tuple, and the type must be `Vec<T>` where \
+                         `T` has a `value_parser`."
                     ),
                 };
 
-                let (span, str_ty) = match subty_if_name(ty, "Vec") {
-                    Some(subty) => {
-                        if is_simple_ty(subty, "String") {
-                            (subty.span(), quote!(::std::string::String))
-                        } else if is_simple_ty(subty, "OsString") {
-                            (subty.span(), quote!(::std::ffi::OsString))
-                        } else {
-                            abort!(
-                                ty.span(),
-                                "The type must be either `Vec<String>` or `Vec<OsString>` \
-                                 to be used with `external_subcommand`."
-                            );
-                        }
-                    }
+                let subty = match subty_if_name(ty, "Vec") {
+                    Some(subty) => subty,
 
                     None => abort!(
                         ty.span(),
-                        "The type must be either `Vec<String>` or `Vec<OsString>` \
+                        "The type must be `Vec<T>` where `T` has a `value_parser` \
                          to be used with `external_subcommand`."
                     ),
                 };
 
-                ext_subcmd = Some((span, &variant.ident, str_ty));
+                ext_subcmd = Some((ty.span(), &variant.ident, subty));
                 None
             } else {
                 Some((variant, item))
@@ -489,43 +601,129 @@ fn gen_from_arg_matches(variants: &[(&Variant, Item)]) -> TokenStream {
             }
         }
     });
-    let child_subcommands = flatten_variants.iter().map(|(variant, _attrs)| {
+    let child_subcommands = flatten_variants.iter().map(|(variant, item)| {
         let variant_name = &variant.ident;
         match variant.fields {
             Unnamed(ref fields) if fields.unnamed.len() == 1 => {
                 let ty = &fields.unnamed[0];
+                match item.flatten_prefix() {
+                    // The child's whole subtree is nested under the single
+                    // `prefix` subcommand (see `gen_augment`), so unwrap that
+                    // one level before delegating: the resulting sub-matches
+                    // look exactly like what the child would see unflattened.
+                    Some(prefix) => quote! {
+                        if __clap_arg_matches.subcommand_name() == Some(#prefix) {
+                            let (_, mut __clap_prefixed_matches) =
+                                __clap_arg_matches.remove_subcommand().unwrap();
+                            let __clap_res = <#ty as clap::FromArgMatches>::from_arg_matches_mut(
+                                &mut __clap_prefixed_matches,
+                            )?;
+                            return ::std::result::Result::Ok(Self :: #variant_name (__clap_res));
+                        }
+                    },
+                    None => quote! {
+                        if __clap_arg_matches
+                            .subcommand_name()
+                            .map(|__clap_name| <#ty as clap::Subcommand>::has_subcommand(__clap_name))
+                            .unwrap_or_default()
+                        {
+                            let __clap_res = <#ty as clap::FromArgMatches>::from_arg_matches_mut(__clap_arg_matches)?;
+                            return ::std::result::Result::Ok(Self :: #variant_name (__clap_res));
+                        }
+                    },
+                }
+            }
+            Named(ref fields) => {
+                let sub_field = find_flatten_subcommand_field(*variant, fields, item);
+                let ty = &sub_field.ty;
+                let all_fields = fields
+                    .named
+                    .iter()
+                    .map(|field| {
+                        let field_item = Item::from_args_field(field, item.casing(), item.env_casing());
+                        (field, field_item)
+                    })
+                    .collect::<Vec<_>>();
+                let constructor_block = args::gen_constructor(&all_fields);
                 quote! {
                     if __clap_arg_matches
                         .subcommand_name()
                         .map(|__clap_name| <#ty as clap::Subcommand>::has_subcommand(__clap_name))
                         .unwrap_or_default()
                     {
-                        let __clap_res = <#ty as clap::FromArgMatches>::from_arg_matches_mut(__clap_arg_matches)?;
-                        return ::std::result::Result::Ok(Self :: #variant_name (__clap_res));
+                        return ::std::result::Result::Ok(Self :: #variant_name #constructor_block);
                     }
                 }
             }
             _ => abort!(
                 variant,
-                "`flatten` is usable only with single-typed tuple variants"
+                "`flatten` is usable only with single-typed tuple variants, \
+                 or struct-style variants with a `#[command(subcommand)]` field"
             ),
         }
     });
 
     let wildcard = match ext_subcmd {
-        Some((span, var_name, str_ty)) => quote_spanned! { span=>
+        Some((span, var_name, subty)) if is_simple_ty(subty, "String") => quote_spanned! { span=>
+                ::std::result::Result::Ok(Self::#var_name(
+                    ::std::iter::once(::std::string::String::from(#subcommand_name_var))
+                    .chain(
+                        #sub_arg_matches_var
+                            .remove_many::<::std::string::String>("")
+                            .unwrap()
+                            .map(::std::string::String::from)
+                    )
+                    .collect::<::std::vec::Vec<_>>()
+                ))
+        },
+
+        Some((span, var_name, subty)) if is_simple_ty(subty, "OsString") => quote_spanned! { span=>
                 ::std::result::Result::Ok(Self::#var_name(
-                    ::std::iter::once(#str_ty::from(#subcommand_name_var))
+                    ::std::iter::once(::std::ffi::OsString::from(#subcommand_name_var))
                     .chain(
                         #sub_arg_matches_var
-                            .remove_many::<#str_ty>("")
+                            .remove_many::<::std::ffi::OsString>("")
                             .unwrap()
-                            .map(#str_ty::from)
+                            .map(::std::ffi::OsString::from)
                     )
                     .collect::<::std::vec::Vec<_>>()
                 ))
         },
 
+        // Arbitrary `Vec<T>`: `value_parser!(T)` only gives us the
+        // type-erased `ValueParser`, which doesn't implement
+        // `TypedValueParser` itself, so it can't be driven directly through
+        // `TypedValueParser::parse_ref`. Route the leading subcommand name
+        // through a throwaway single-arg `Command` instead, which parses it
+        // with the exact same `ValueParser` clap would use for a real `Arg`,
+        // surfacing a conversion failure as a `clap::Error` instead of
+        // panicking.
+        Some((span, var_name, subty)) => quote_spanned! { span=>
+                {
+                    let __clap_value_parser = clap::value_parser!(#subty);
+                    let mut __clap_name_matches = clap::Command::new("")
+                        .arg(
+                            clap::Arg::new("value")
+                                .index(1)
+                                .required(true)
+                                .value_parser(__clap_value_parser),
+                        )
+                        .try_get_matches_from(["", #subcommand_name_var.as_str()])?;
+                    let __clap_name_typed: #subty = __clap_name_matches
+                        .remove_one("value")
+                        .expect("required");
+                    ::std::result::Result::Ok(Self::#var_name(
+                        ::std::iter::once(__clap_name_typed)
+                            .chain(
+                                #sub_arg_matches_var
+                                    .remove_many::<#subty>("")
+                                    .unwrap_or_default()
+                            )
+                            .collect::<::std::vec::Vec<_>>()
+                    ))
+                }
+        },
+
         None => quote! {
             ::std::result::Result::Err(clap::Error::raw(clap::error::ErrorKind::InvalidSubcommand, format!("The subcommand '{}' wasn't recognized", #subcommand_name_var)))
         },
@@ -611,15 +809,65 @@ fn gen_update_from_arg_matches(variants: &[(&Variant, Item)]) -> TokenStream {
         }
     });
 
-    let child_subcommands = flatten.iter().map(|(variant, _attrs)| {
+    let child_subcommands = flatten.iter().map(|(variant, item)| {
         let variant_name = &variant.ident;
         match variant.fields {
             Unnamed(ref fields) if fields.unnamed.len() == 1 => {
                 let ty = &fields.unnamed[0];
+                match item.flatten_prefix() {
+                    // Same nesting as in `gen_from_arg_matches`: unwrap the
+                    // `prefix` subcommand first so the child only ever sees
+                    // its own, unprefixed `ArgMatches`.
+                    Some(prefix) => quote! {
+                        if __clap_name == #prefix {
+                            if let Self :: #variant_name (child) = s {
+                                let (_, mut __clap_prefixed_matches) =
+                                    __clap_arg_matches.remove_subcommand().unwrap();
+                                <#ty as clap::FromArgMatches>::update_from_arg_matches_mut(
+                                    child,
+                                    &mut __clap_prefixed_matches,
+                                )?;
+                                return ::std::result::Result::Ok(());
+                            }
+                        }
+                    },
+                    None => quote! {
+                        if <#ty as clap::Subcommand>::has_subcommand(__clap_name) {
+                            if let Self :: #variant_name (child) = s {
+                                <#ty as clap::FromArgMatches>::update_from_arg_matches_mut(child, __clap_arg_matches)?;
+                                return ::std::result::Result::Ok(());
+                            }
+                        }
+                    },
+                }
+            }
+            Named(ref fields) => {
+                let sub_field = find_flatten_subcommand_field(*variant, fields, item);
+                let ty = &sub_field.ty;
+                let field_names = fields
+                    .named
+                    .iter()
+                    .map(|field| field.ident.as_ref().unwrap())
+                    .collect::<Vec<_>>();
+                // Update every field, not just the delegated subcommand one:
+                // `args::gen_updater` already knows how to drive a
+                // `Kind::Subcommand` field through its own
+                // `update_from_arg_matches_mut`, so this also merges the
+                // variant's other ("common") `Args` fields in place, the same
+                // way the non-flatten `Named` arm above does.
+                let all_fields = fields
+                    .named
+                    .iter()
+                    .map(|field| {
+                        let field_item = Item::from_args_field(field, item.casing(), item.env_casing());
+                        (field, field_item)
+                    })
+                    .collect::<Vec<_>>();
+                let update = args::gen_updater(&all_fields, false);
                 quote! {
                     if <#ty as clap::Subcommand>::has_subcommand(__clap_name) {
-                        if let Self :: #variant_name (child) = s {
-                            <#ty as clap::FromArgMatches>::update_from_arg_matches_mut(child, __clap_arg_matches)?;
+                        if let Self :: #variant_name { #( #field_names, )* } = s {
+                            #update
                             return ::std::result::Result::Ok(());
                         }
                     }
@@ -627,7 +875,8 @@ fn gen_update_from_arg_matches(variants: &[(&Variant, Item)]) -> TokenStream {
             }
             _ => abort!(
                 variant,
-                "`flatten` is usable only with single-typed tuple variants"
+                "`flatten` is usable only with single-typed tuple variants, \
+                 or struct-style variants with a `#[command(subcommand)]` field"
             ),
         }
     });
@@ -643,6 +892,13 @@ fn gen_update_from_arg_matches(variants: &[(&Variant, Item)]) -> TokenStream {
             if let Some(__clap_name) = __clap_arg_matches.subcommand_name() {
                 match self {
                     #( #subcommands ),*
+                    // `self` is a flattened variant, or doesn't match the
+                    // incoming subcommand by name. Each `child_subcommands`
+                    // check below is responsible for recognizing the former
+                    // case and updating the already-flattened child in place
+                    // (preserving any state it already holds); when no check
+                    // both owns the name and matches the current variant of
+                    // `self`, we fall back to rebuilding `self` from scratch.
                     s => {
                         #( #child_subcommands )*
                         *s = <Self as clap::FromArgMatches>::from_arg_matches_mut(__clap_arg_matches)?;
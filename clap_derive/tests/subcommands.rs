@@ -0,0 +1,138 @@
+use std::path::PathBuf;
+
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+
+#[derive(Parser, PartialEq, Debug)]
+struct Opt {
+    #[command(subcommand)]
+    sub: Sub,
+}
+
+#[derive(Subcommand, PartialEq, Debug)]
+enum Sub {
+    Known,
+
+    #[command(external_subcommand)]
+    External(Vec<PathBuf>),
+}
+
+#[test]
+fn external_subcommand_collects_typed_values() {
+    let opt = Opt::parse_from(["test", "cp", "src/main.rs", "dst/main.rs"]);
+    assert_eq!(
+        opt,
+        Opt {
+            sub: Sub::External(vec![
+                PathBuf::from("cp"),
+                PathBuf::from("src/main.rs"),
+                PathBuf::from("dst/main.rs"),
+            ])
+        }
+    );
+}
+
+#[test]
+fn external_subcommand_still_dispatches_known_variants() {
+    let opt = Opt::parse_from(["test", "known"]);
+    assert_eq!(opt, Opt { sub: Sub::Known });
+}
+
+#[derive(Subcommand, PartialEq, Debug)]
+enum Inner {
+    Build,
+    Test,
+}
+
+#[derive(Subcommand, PartialEq, Debug)]
+enum Outer {
+    Direct,
+
+    #[command(flatten)]
+    Delegated {
+        #[arg(long, global = true)]
+        verbose: bool,
+        #[command(subcommand)]
+        inner: Inner,
+    },
+}
+
+#[derive(Parser, PartialEq, Debug)]
+struct OuterOpt {
+    #[command(subcommand)]
+    sub: Outer,
+}
+
+#[test]
+fn struct_style_flatten_update_refreshes_every_field_not_just_the_subcommand() {
+    let mut opt = OuterOpt::parse_from(["prog", "build"]);
+    assert_eq!(
+        opt,
+        OuterOpt {
+            sub: Outer::Delegated {
+                verbose: false,
+                inner: Inner::Build,
+            }
+        }
+    );
+
+    let matches = OuterOpt::command().get_matches_from(["prog", "--verbose", "test"]);
+    opt.update_from_arg_matches(&matches).unwrap();
+
+    // Both the delegated subcommand field *and* the plain `verbose` field
+    // must be refreshed: the update drives every field of the variant
+    // through `gen_updater`, not only the subcommand one.
+    assert_eq!(
+        opt,
+        OuterOpt {
+            sub: Outer::Delegated {
+                verbose: true,
+                inner: Inner::Test,
+            }
+        }
+    );
+}
+
+#[derive(Subcommand, PartialEq, Debug)]
+enum Left {
+    Run,
+}
+
+#[derive(Subcommand, PartialEq, Debug)]
+enum Right {
+    Run,
+}
+
+#[derive(Subcommand, PartialEq, Debug)]
+enum Combined {
+    #[command(flatten, prefix = "left")]
+    Left(Left),
+    #[command(flatten, prefix = "right")]
+    Right(Right),
+}
+
+#[derive(Parser, PartialEq, Debug)]
+struct CombinedOpt {
+    #[command(subcommand)]
+    sub: Combined,
+}
+
+#[test]
+fn prefixed_flatten_variants_dispatch_without_colliding_on_a_shared_name() {
+    // Both `Left` and `Right` define a `Run` variant; without the prefix
+    // nesting, one of the two `run` subcommands would shadow the other.
+    let opt = CombinedOpt::parse_from(["prog", "left", "run"]);
+    assert_eq!(
+        opt,
+        CombinedOpt {
+            sub: Combined::Left(Left::Run)
+        }
+    );
+
+    let opt = CombinedOpt::parse_from(["prog", "right", "run"]);
+    assert_eq!(
+        opt,
+        CombinedOpt {
+            sub: Combined::Right(Right::Run)
+        }
+    );
+}
@@ -0,0 +1,184 @@
+//! Generates a machine-readable JSON dump of a [`clap`](https://github.com/clap-rs/clap)
+//! [`Command`] tree, for tooling that would rather consume one document than
+//! re-walk clap internals (e.g. alternate shell completion backends).
+
+use clap::{Arg, Command};
+use clap_complete::Generator;
+
+/// Generate a JSON description of the command tree
+pub struct Json;
+
+impl Generator for Json {
+    fn file_name(&self, name: &str) -> String {
+        format!("{}.json", name)
+    }
+
+    fn generate(&self, cmd: &Command, buf: &mut dyn std::io::Write) {
+        let mut json = String::new();
+        write_command(&mut json, cmd);
+
+        buf.write_all(json.as_bytes())
+            .expect("Failed to write to generated file")
+    }
+}
+
+fn write_command(out: &mut String, cmd: &Command) {
+    out.push('{');
+
+    push_field(out, "name", &json_string(cmd.get_name()));
+    out.push(',');
+
+    push_field(
+        out,
+        "about",
+        &cmd.get_about()
+            .map(|about| json_string(&about.to_string()))
+            .unwrap_or_else(|| "null".to_string()),
+    );
+    out.push(',');
+
+    push_field(out, "args", &json_array(cmd.get_arguments(), write_arg));
+    out.push(',');
+
+    push_field(
+        out,
+        "subcommands",
+        &json_array(cmd.get_subcommands(), write_command),
+    );
+
+    out.push('}');
+}
+
+fn write_arg(out: &mut String, arg: &Arg) {
+    out.push('{');
+
+    push_field(out, "id", &json_string(arg.get_id().as_str()));
+    out.push(',');
+
+    push_field(
+        out,
+        "shorts",
+        &json_array(
+            arg.get_short_and_visible_aliases()
+                .unwrap_or_default()
+                .iter(),
+            |out, short| out.push_str(&json_string(&short.to_string())),
+        ),
+    );
+    out.push(',');
+
+    push_field(
+        out,
+        "longs",
+        &json_array(
+            arg.get_long_and_visible_aliases()
+                .unwrap_or_default()
+                .iter(),
+            |out, long| out.push_str(&json_string(long)),
+        ),
+    );
+    out.push(',');
+
+    push_field(
+        out,
+        "takes_values",
+        &arg.get_num_args()
+            .map(|v| v.takes_values())
+            .unwrap_or(false)
+            .to_string(),
+    );
+    out.push(',');
+
+    push_field(out, "required", &arg.is_required_set().to_string());
+    out.push(',');
+
+    push_field(
+        out,
+        "value_hint",
+        &json_string(&format!("{:?}", arg.get_value_hint())),
+    );
+    out.push(',');
+
+    push_field(
+        out,
+        "possible_values",
+        &json_array(arg.get_possible_values().iter(), |out, value| {
+            out.push_str(&json_string(value.get_name()))
+        }),
+    );
+
+    out.push('}');
+}
+
+fn push_field(out: &mut String, name: &str, value: &str) {
+    out.push_str(&json_string(name));
+    out.push(':');
+    out.push_str(value);
+}
+
+fn json_array<T>(
+    items: impl Iterator<Item = T>,
+    mut write_item: impl FnMut(&mut String, T),
+) -> String {
+    let mut out = String::from("[");
+    let mut first = true;
+
+    for item in items {
+        if !first {
+            out.push(',');
+        }
+        first = false;
+        write_item(&mut out, item);
+    }
+
+    out.push(']');
+    out
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_command_serializes_args_and_possible_values() {
+        let cmd = Command::new("app").about("does things").arg(
+            Arg::new("level")
+                .long("level")
+                .value_parser(["low", "high"])
+                .required(true),
+        );
+
+        let mut out = String::new();
+        write_command(&mut out, &cmd);
+
+        assert!(out.contains(r#""name":"app""#));
+        assert!(out.contains(r#""about":"does things""#));
+        assert!(out.contains(r#""longs":["level"]"#));
+        assert!(out.contains(r#""required":true"#));
+        assert!(out.contains(r#""possible_values":["low","high"]"#));
+    }
+
+    #[test]
+    fn json_string_escapes_control_and_special_characters() {
+        assert_eq!(json_string("a\"b\\c\nd"), r#""a\"b\\c\nd""#);
+    }
+}
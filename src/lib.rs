@@ -1,8 +1,13 @@
 //! Generates [Nushell](https://github.com/nushell/nushell) completions for [`clap`](https://github.com/clap-rs/clap) based CLIs
 
-use clap::{Arg, Command};
+use std::any::TypeId;
+
+use clap::{Arg, Command, ValueHint};
 use clap_complete::Generator;
 
+mod json;
+pub use json::Json;
+
 /// Generate Nushell complete file
 pub struct Nushell;
 
@@ -10,46 +15,146 @@ enum Argument {
     Short(Vec<char>),
     Long(Vec<String>),
     ShortAndLong(Vec<char>, Vec<String>),
-    Positional(String, bool),
+    /// name, required, variadic (accepts more than one value)
+    Positional(String, bool, bool),
 }
 
 struct ArgumentLine {
     arg: Argument,
     takes_values: bool,
+    /// The argument may be supplied more than once, or (for positionals) may
+    /// itself consume more than one value.
+    multiple: bool,
+    nu_type: &'static str,
+    completer: Option<String>,
     help: Option<String>,
 }
 
 impl ArgumentLine {
     fn append_type_and_help(&self, s: &mut String) {
         if self.takes_values {
-            s.push_str(": string");
+            s.push_str(self.nu_type);
+
+            if let Some(completer) = &self.completer {
+                s.push_str(format!("@\"{}\"", completer).as_str());
+            }
         }
 
-        if let Some(help) = &self.help {
-            s.push_str(format!("\t# {}", help).as_str());
+        match (
+            &self.help,
+            self.multiple && !matches!(self.arg, Argument::Positional(..)),
+        ) {
+            (Some(help), true) => s.push_str(format!("\t# {} (may be repeated)", help).as_str()),
+            (Some(help), false) => s.push_str(format!("\t# {}", help).as_str()),
+            (None, true) => s.push_str("\t# (may be repeated)"),
+            (None, false) => {}
         }
 
         s.push('\n');
     }
 }
 
-impl From<&Arg> for ArgumentLine {
-    fn from(arg: &Arg) -> Self {
+/// Whether `arg` can take, or be supplied, more than one value: a variadic
+/// positional (`num_args` with a max greater than one or unbounded) or a
+/// repeatable option (`ArgAction::Append`, or `num_args` greater than one).
+fn is_multiple(arg: &Arg) -> bool {
+    if matches!(arg.get_action(), clap::ArgAction::Append) {
+        return true;
+    }
+
+    arg.get_num_args()
+        .map(|range| range.max_values() > 1)
+        .unwrap_or(false)
+}
+
+/// Name of the Nushell `def` generated for `arg`'s possible-value completions.
+fn nu_complete_name(bin_name: &str, arg: &Arg) -> String {
+    format!("nu-complete {} {}", bin_name, arg.get_id())
+}
+
+/// Emit a `def "nu-complete ..." [] { [ ... ] }` completer for every argument
+/// of `cmd` that has a fixed set of possible values.
+fn generate_completers(completions: &mut String, cmd: &Command, bin_name: &str) {
+    for arg in cmd.get_arguments() {
+        let possible_values = arg.get_possible_values();
+        if possible_values.is_empty() {
+            continue;
+        }
+
+        completions.push_str(
+            format!(
+                "  def \"{}\" [] {{\n    [\n",
+                nu_complete_name(bin_name, arg)
+            )
+            .as_str(),
+        );
+
+        for value in &possible_values {
+            completions.push_str(format!("      \"{}\"", value.get_name()).as_str());
+            if let Some(help) = value.get_help() {
+                completions.push_str(format!("\t# {}", help).as_str());
+            }
+            completions.push('\n');
+        }
+
+        completions.push_str("    ]\n  }\n\n");
+    }
+}
+
+/// Map a clap argument's value parser / value hint to the closest Nushell
+/// type annotation (e.g. `: int`, `: path`), defaulting to `: string`.
+fn infer_nu_type(arg: &Arg) -> &'static str {
+    match arg.get_value_hint() {
+        ValueHint::FilePath | ValueHint::AnyPath | ValueHint::ExecutablePath => return ": path",
+        ValueHint::DirPath => return ": directory",
+        _ => {}
+    }
+
+    let type_id = arg.get_value_parser().type_id();
+
+    if type_id == TypeId::of::<bool>() {
+        ": bool"
+    } else if type_id == TypeId::of::<i8>()
+        || type_id == TypeId::of::<i16>()
+        || type_id == TypeId::of::<i32>()
+        || type_id == TypeId::of::<i64>()
+        || type_id == TypeId::of::<u8>()
+        || type_id == TypeId::of::<u16>()
+        || type_id == TypeId::of::<u32>()
+        || type_id == TypeId::of::<u64>()
+    {
+        ": int"
+    } else if type_id == TypeId::of::<f32>() || type_id == TypeId::of::<f64>() {
+        ": number"
+    } else {
+        ": string"
+    }
+}
+
+impl ArgumentLine {
+    fn new(arg: &Arg, bin_name: &str) -> Self {
         let takes_values = arg
             .get_num_args()
             .map(|v| v.takes_values())
             .unwrap_or(false);
 
+        let nu_type = infer_nu_type(arg);
+        let multiple = is_multiple(arg);
         let help = arg.get_help().map(|s| s.to_string());
+        let completer =
+            (!arg.get_possible_values().is_empty()).then(|| nu_complete_name(bin_name, arg));
 
         if arg.is_positional() {
             let id = arg.get_id().to_string();
             let required = arg.is_required_set();
-            let arg = Argument::Positional(id, required);
+            let arg = Argument::Positional(id, required, multiple);
 
             return Self {
                 arg,
                 takes_values,
+                multiple,
+                nu_type,
+                completer,
                 help,
             };
         }
@@ -65,11 +170,17 @@ impl From<&Arg> for ArgumentLine {
                         longs.iter().map(|s| s.to_string()).collect(),
                     ),
                     takes_values,
+                    multiple,
+                    nu_type,
+                    completer,
                     help,
                 },
                 None => Self {
                     arg: Argument::Short(shorts),
                     takes_values,
+                    multiple,
+                    nu_type,
+                    completer,
                     help,
                 },
             },
@@ -77,6 +188,9 @@ impl From<&Arg> for ArgumentLine {
                 Some(long) => Self {
                     arg: Argument::Long(long.iter().map(|s| s.to_string()).collect()),
                     takes_values,
+                    multiple,
+                    nu_type,
+                    completer,
                     help,
                 },
                 None => unreachable!("No short or long option found"),
@@ -125,11 +239,15 @@ impl ToString for ArgumentLine {
                     self.append_type_and_help(&mut s);
                 }
             }
-            Argument::Positional(positional, required) => {
-                s.push_str(format!("    {}", positional).as_str());
-
-                if !*required {
-                    s.push('?');
+            Argument::Positional(positional, required, variadic) => {
+                if *variadic {
+                    s.push_str(format!("    ...{}", positional).as_str());
+                } else {
+                    s.push_str(format!("    {}", positional).as_str());
+
+                    if !*required {
+                        s.push('?');
+                    }
                 }
 
                 self.append_type_and_help(&mut s);
@@ -177,11 +295,13 @@ fn generate_completion(completions: &mut String, cmd: &Command, is_subcommand: b
         bin_name.into()
     };
 
+    generate_completers(completions, cmd, bin_name);
+
     completions.push_str(format!("  export extern {} [\n", name).as_str());
 
     let s: String = cmd
         .get_arguments()
-        .map(|arg| ArgumentLine::from(arg).to_string())
+        .map(|arg| ArgumentLine::new(arg, bin_name).to_string())
         .collect();
 
     completions.push_str(&s);
@@ -194,3 +314,70 @@ fn generate_completion(completions: &mut String, cmd: &Command, is_subcommand: b
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn infer_nu_type_maps_value_hint_and_parser() {
+        let path_arg = Arg::new("path")
+            .long("path")
+            .value_hint(ValueHint::FilePath);
+        assert_eq!(infer_nu_type(&path_arg), ": path");
+
+        let dir_arg = Arg::new("dir").long("dir").value_hint(ValueHint::DirPath);
+        assert_eq!(infer_nu_type(&dir_arg), ": directory");
+
+        let int_arg = Arg::new("count")
+            .long("count")
+            .value_parser(clap::value_parser!(i64));
+        assert_eq!(infer_nu_type(&int_arg), ": int");
+
+        let bool_arg = Arg::new("flag")
+            .long("flag")
+            .value_parser(clap::value_parser!(bool));
+        assert_eq!(infer_nu_type(&bool_arg), ": bool");
+
+        let string_arg = Arg::new("name").long("name");
+        assert_eq!(infer_nu_type(&string_arg), ": string");
+    }
+
+    #[test]
+    fn generate_completers_emits_a_def_for_possible_values() {
+        let cmd = Command::new("app").arg(
+            Arg::new("level")
+                .long("level")
+                .value_parser(["low", "high"]),
+        );
+
+        let mut completions = String::new();
+        generate_completers(&mut completions, &cmd, "app");
+
+        assert!(completions.contains(r#"def "nu-complete app level" [] {"#));
+        assert!(completions.contains(r#""low""#));
+        assert!(completions.contains(r#""high""#));
+
+        let line = ArgumentLine::new(cmd.get_arguments().next().unwrap(), "app");
+        assert_eq!(line.completer.as_deref(), Some("nu-complete app level"));
+    }
+
+    #[test]
+    fn is_multiple_detects_append_and_variadic_positionals() {
+        let appended = Arg::new("tag").long("tag").action(clap::ArgAction::Append);
+        assert!(is_multiple(&appended));
+
+        let variadic = Arg::new("files").num_args(1..);
+        assert!(is_multiple(&variadic));
+
+        let single = Arg::new("name").long("name");
+        assert!(!is_multiple(&single));
+    }
+
+    #[test]
+    fn to_string_renders_variadic_positional_with_rest_syntax() {
+        let arg = Arg::new("files").num_args(1..).required(true);
+        let line = ArgumentLine::new(&arg, "app");
+        assert_eq!(line.to_string(), "    ...files: string\n");
+    }
+}